@@ -1,6 +1,163 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::Neg;
+use std::str::FromStr;
 
-pub type Currency = f64;
+/// Number of fractional digits a [`Currency`] amount is scaled by.
+const SCALE_DIGITS: usize = 4;
+const SCALE: i64 = 10_000;
+
+/// A fixed-point money amount, stored internally as ten-thousandths of a unit.
+///
+/// Using a scaled `i64` instead of `f64` means amounts never accumulate
+/// floating-point representation error and arithmetic can be checked for
+/// overflow instead of relying on `is_finite`/sign checks after the fact.
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Debug, Default, Hash)]
+pub struct Currency(i64);
+
+impl Currency {
+    pub const ZERO: Currency = Currency(0);
+
+    pub fn checked_add(self, rhs: Currency) -> Option<Currency> {
+        self.0.checked_add(rhs.0).map(Currency)
+    }
+
+    pub fn checked_sub(self, rhs: Currency) -> Option<Currency> {
+        self.0.checked_sub(rhs.0).map(Currency)
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Parses a CSV-style decimal string (e.g. `"1.5"`, `"-3"`) into a scaled
+    /// [`Currency`]. Rejects inputs with more than [`SCALE_DIGITS`] fractional
+    /// digits rather than silently rounding them away.
+    fn parse(input: &str) -> Result<Self, CurrencyParseError> {
+        let input = input.trim();
+        let (negative, unsigned) = match input.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+
+        let (whole, fraction) = match unsigned.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (unsigned, ""),
+        };
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(CurrencyParseError::Empty);
+        }
+        if fraction.len() > SCALE_DIGITS {
+            return Err(CurrencyParseError::TooManyFractionalDigits);
+        }
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CurrencyParseError::InvalidDigits);
+        }
+
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| CurrencyParseError::InvalidDigits)?
+        };
+        let mut fraction_value: i64 = if fraction.is_empty() {
+            0
+        } else {
+            fraction
+                .parse()
+                .map_err(|_| CurrencyParseError::InvalidDigits)?
+        };
+        for _ in fraction.len()..SCALE_DIGITS {
+            fraction_value = fraction_value
+                .checked_mul(10)
+                .ok_or(CurrencyParseError::Overflow)?;
+        }
+
+        let scaled = whole
+            .checked_mul(SCALE)
+            .and_then(|whole| whole.checked_add(fraction_value))
+            .ok_or(CurrencyParseError::Overflow)?;
+
+        Ok(Currency(if negative { -scaled } else { scaled }))
+    }
+}
+
+impl Neg for Currency {
+    type Output = Currency;
+
+    fn neg(self) -> Currency {
+        Currency(-self.0)
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let magnitude = self.0.unsigned_abs();
+        let whole = magnitude / SCALE as u64;
+        let mut fraction = magnitude % SCALE as u64;
+        // Trim trailing zeros so amounts serialize with up to SCALE_DIGITS
+        // fractional digits instead of always padding to exactly that many,
+        // keeping at least one so the fractional part never disappears.
+        let mut digits = SCALE_DIGITS;
+        while digits > 1 && fraction % 10 == 0 {
+            fraction /= 10;
+            digits -= 1;
+        }
+        if self.0 < 0 {
+            write!(f, "-")?;
+        }
+        write!(f, "{whole}.{fraction:0width$}", width = digits)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Currency::parse(input)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CurrencyParseError {
+    Empty,
+    TooManyFractionalDigits,
+    InvalidDigits,
+    Overflow,
+}
+
+impl fmt::Display for CurrencyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "currency amount is empty"),
+            Self::TooManyFractionalDigits => {
+                write!(f, "currency amount has more than {SCALE_DIGITS} fractional digits")
+            }
+            Self::InvalidDigits => write!(f, "currency amount contains non-numeric digits"),
+            Self::Overflow => write!(f, "currency amount overflows i64"),
+        }
+    }
+}
+
+impl std::error::Error for CurrencyParseError {}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+        Currency::parse(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
 
 #[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
@@ -14,6 +171,16 @@ pub enum Operation {
 
 pub type TransactionId = u32;
 
+pub type AssetId = u16;
+
+/// The asset a `Transaction`/`Client` row refers to when the CSV omits an
+/// `asset` column, preserving behavior for single-asset inputs.
+pub const BASE_ASSET: AssetId = 0;
+
+fn base_asset() -> AssetId {
+    BASE_ASSET
+}
+
 #[derive(Copy, Clone, Debug, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "tx")]
@@ -22,6 +189,8 @@ pub struct Transaction {
     pub operation: Operation,
     #[serde(rename = "client")]
     pub client_id: ClientId,
+    #[serde(rename = "asset", default = "base_asset")]
+    pub asset_id: AssetId,
     pub amount: Option<Currency>,
 }
 
@@ -31,6 +200,8 @@ pub type ClientId = u16;
 pub struct Client {
     #[serde(rename = "client")]
     pub id: ClientId,
+    #[serde(rename = "asset")]
+    pub asset_id: AssetId,
     #[serde(rename = "available")]
     pub balance: Currency,
     #[serde(rename = "held")]
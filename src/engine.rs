@@ -1,28 +1,9 @@
-use super::api::{Client, ClientId, Currency, Operation, Transaction, TransactionId};
-use anyhow::{anyhow, Result};
-use std::collections::{hash_map::Entry, HashMap};
-use std::ops::Neg;
+use super::api::{AssetId, Client, ClientId, Currency, Operation, Transaction};
+use super::error::EngineError;
+use super::store::{Account, Adjustment, AdjustmentState, Lock, MemStore, Store};
+use std::collections::HashMap;
 
-#[derive(Default)]
-struct Account {
-    is_frozen: bool,
-    balance: Currency,
-    disputing: Currency,
-}
-
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum AdjustmentState {
-    Valid,
-    UnderDispute,
-    Invalid,
-}
-
-#[derive(Copy, Clone, Debug)]
-struct Adjustment {
-    account_id: ClientId,
-    amount: Currency,
-    state: AdjustmentState,
-}
+type Result<T> = std::result::Result<T, EngineError>;
 
 #[derive(Debug)]
 struct AccountTransaction {
@@ -35,183 +16,424 @@ struct AccountTransaction {
 impl AccountTransaction {
     fn apply(
         account: &mut Account,
+        asset_id: AssetId,
         adjustment: &mut Adjustment,
         update_tx: impl FnOnce(&mut Self) -> Result<()>,
     ) -> Result<()> {
-        // Check if the AccountTransaction can even occur
+        // Check if the AccountTransaction can even occur. An Invalid
+        // adjustment is only ever produced by a chargeback, which freezes
+        // the whole account in the same step, so that case is already
+        // covered by the is_frozen check below.
         if account.is_frozen {
-            return Err(anyhow!("transaction attempt on frozen account"));
-        }
-        if adjustment.state == AdjustmentState::Invalid {
-            return Err(anyhow!("invalid transaction"));
+            return Err(EngineError::FrozenAccount);
         }
 
+        // Get or create the sub-account for the asset this transaction touches
+        let asset = account.assets.entry(asset_id).or_default();
+
         // Perform the AccountTransaction
         let mut tx = Self {
             amount: adjustment.amount,
-            balance: account.balance,
-            disputing: account.disputing,
+            balance: asset.balance,
+            disputing: asset.disputing,
             state: adjustment.state,
         };
         update_tx(&mut tx)?;
 
-        // Make sure the balance is always valid (real number and not negative)
-        if !tx.balance.is_finite() || tx.balance < 0.0 {
-            return Err(anyhow!("transaction invalidated client balance"));
-        }
-
-        // Make sure the dispute amount is always valid (can be negative when disputing withdrawals)
-        if !tx.disputing.is_finite() {
-            return Err(anyhow!("transaction invalidated client dispute balance"));
+        // Make sure the balance is always valid (did not overflow and not negative)
+        if tx.balance.is_negative() {
+            return Err(EngineError::InsufficientFunds);
         }
 
         // Commit it to the Account and Adjustment
-        Ok({
-            adjustment.state = tx.state;
-            account.balance = tx.balance;
-            account.disputing = tx.disputing;
-            account.is_frozen = tx.state == AdjustmentState::Invalid;
-        })
+        adjustment.state = tx.state;
+        asset.balance = tx.balance;
+        asset.disputing = tx.disputing;
+        account.is_frozen = tx.state == AdjustmentState::Invalid;
+        Ok(())
     }
 }
 
+/// Applies transactions to accounts held in a [`Store`], defaulting to the
+/// in-memory [`MemStore`] but generic so a disk-backed store can be plugged
+/// in for ledgers that don't fit in RAM.
 #[derive(Default)]
-pub struct Engine {
-    accounts: HashMap<ClientId, Account>,
-    adjustments: HashMap<TransactionId, Adjustment>,
+pub struct Engine<S: Store = MemStore> {
+    store: S,
+    /// Number of transactions handed to [`Engine::apply`] so far, used as the
+    /// clock that [`Lock::until_sequence`] counts down against.
+    sequence: u64,
+    /// Running total of funds in circulation per asset (deposits minus
+    /// withdrawals minus chargebacks minus slashes), tracked independently of
+    /// account balances so [`Engine::assert_invariant`] can catch the two
+    /// diverging.
+    total_issuance: HashMap<AssetId, Currency>,
 }
 
-impl Engine {
+impl<S: Store> Engine<S> {
     pub fn new() -> Self {
+        Self::with_store(S::default())
+    }
+
+    pub fn with_store(store: S) -> Self {
         Self {
-            accounts: HashMap::new(),
-            adjustments: HashMap::new(),
+            store,
+            sequence: 0,
+            total_issuance: HashMap::new(),
         }
     }
 
     pub fn clients(&self) -> impl Iterator<Item = Client> + '_ {
-        self.accounts.iter().map(|(account_id, account)| Client {
-            id: *account_id,
-            allocated: account.balance + account.disputing,
-            balance: account.balance,
-            under_dispute: account.disputing,
-            is_frozen: account.is_frozen,
+        self.store.accounts().flat_map(|(account_id, account)| {
+            let is_frozen = account.is_frozen;
+            account
+                .assets
+                .into_iter()
+                .map(move |(asset_id, asset)| Client {
+                    id: account_id,
+                    asset_id,
+                    allocated: asset
+                        .balance
+                        .checked_add(asset.disputing)
+                        .expect("account balance and disputing amount overflowed on report"),
+                    balance: asset.balance,
+                    under_dispute: asset.disputing,
+                    is_frozen,
+                })
         })
     }
 
     pub fn apply(&mut self, transaction: Transaction) -> Result<()> {
+        self.sequence += 1;
         match transaction.operation {
             Operation::Deposit => {
                 let to_accredit = |amount: Currency| amount;
-                self.insert_transaction(transaction, to_accredit)
+                self.insert_transaction(transaction, false, to_accredit)
             }
             Operation::Withdrawal => {
-                let to_accredit = |amount: Currency| f64::neg(amount);
-                self.insert_transaction(transaction, to_accredit)
+                let to_accredit = |amount: Currency| -amount;
+                self.insert_transaction(transaction, true, to_accredit)
+            }
+            Operation::Dispute => self
+                .update_transaction(transaction, |tx| {
+                    if tx.state != AdjustmentState::Valid {
+                        return Err(EngineError::AlreadyDisputed);
+                    }
+
+                    // Clients available funds are transferred to disputing
+                    tx.balance = tx
+                        .balance
+                        .checked_sub(tx.amount)
+                        .ok_or(EngineError::InvalidAmount)?;
+                    tx.disputing = tx
+                        .disputing
+                        .checked_add(tx.amount)
+                        .ok_or(EngineError::InvalidAmount)?;
+                    tx.state = AdjustmentState::UnderDispute;
+                    Ok(())
+                })
+                .map(|_| ()),
+            Operation::Resolve => self
+                .update_transaction(transaction, |tx| {
+                    if tx.state != AdjustmentState::UnderDispute {
+                        return Err(EngineError::NotUnderDispute);
+                    }
+
+                    // Clients disputing funds are transferred to available
+                    tx.disputing = tx
+                        .disputing
+                        .checked_sub(tx.amount)
+                        .ok_or(EngineError::InvalidAmount)?;
+                    tx.balance = tx
+                        .balance
+                        .checked_add(tx.amount)
+                        .ok_or(EngineError::InvalidAmount)?;
+                    tx.state = AdjustmentState::Valid;
+                    Ok(())
+                })
+                .map(|_| ()),
+            Operation::Chargeback => {
+                let adjustment = self.update_transaction(transaction, |tx| {
+                    if tx.state != AdjustmentState::UnderDispute {
+                        return Err(EngineError::NotUnderDispute);
+                    }
+
+                    // Clients disputing funds and available funds are removed.
+                    // An invalid transaction locks the account from further transactions
+                    tx.disputing = tx
+                        .disputing
+                        .checked_sub(tx.amount)
+                        .ok_or(EngineError::InvalidAmount)?;
+                    tx.state = AdjustmentState::Invalid;
+                    Ok(())
+                })?;
+
+                // A chargeback permanently removes the disputed amount from
+                // circulation (deposit case) or restores it (withdrawal case,
+                // since `amount` is negative) — either way it's the same
+                // delta the disputing bucket just moved by.
+                self.adjust_issuance(adjustment.asset_id, -adjustment.amount)
             }
-            Operation::Dispute => self.update_transaction(transaction, |tx| {
-                if tx.state != AdjustmentState::Valid {
-                    return Err(anyhow!("disputing an invalid transaction"));
-                }
-
-                // Clients available funds are transferred to disputing
-                tx.balance -= tx.amount;
-                tx.disputing += tx.amount;
-                tx.state = AdjustmentState::UnderDispute;
-                Ok(())
-            }),
-            Operation::Resolve => self.update_transaction(transaction, |tx| {
-                if tx.state != AdjustmentState::UnderDispute {
-                    return Err(anyhow!("resolving a transaction not under dispute"));
-                }
-
-                // Clients disputing funds are transferred to available
-                tx.disputing -= tx.amount;
-                tx.balance += tx.amount;
-                tx.state = AdjustmentState::Valid;
-                Ok(())
-            }),
-            Operation::Chargeback => self.update_transaction(transaction, |tx| {
-                if tx.state != AdjustmentState::UnderDispute {
-                    return Err(anyhow!("invalidating a transaction not under dispute"));
-                }
-
-                // Clients disputing funds and available funds are removed.
-                // An invalid transaction locks the account from further transactions
-                tx.disputing -= tx.amount;
-                tx.state = AdjustmentState::Invalid;
-                Ok(())
-            }),
         }
     }
 
     fn insert_transaction(
         &mut self,
         transaction: Transaction,
+        is_withdrawal: bool,
         to_accredit: impl FnOnce(Currency) -> Currency,
     ) -> Result<()> {
         let amount = transaction.amount;
         let adjustment_id = transaction.id;
         let account_id = transaction.client_id;
+        let asset_id = transaction.asset_id;
 
         // Validate the transaction amount passed in
-        let amount = amount.ok_or(anyhow!("transaction amount missing"))?;
-        if !amount.is_finite() {
-            return Err(anyhow!("invalid transaction amount"));
-        }
-
-        match self.adjustments.entry(adjustment_id) {
-            Entry::Occupied(_) => Err(anyhow!("transaction already exists")),
-            Entry::Vacant(entry) => {
-                // Get or create the Account if it doesn't exist.
-                // Also prepare the corresponding Adjustment.
-                let account = self.accounts.entry(account_id).or_default();
-                let mut adjustment = Adjustment {
-                    account_id,
-                    amount: to_accredit(amount),
-                    state: AdjustmentState::Valid,
-                };
-
-                // Try to apply the transaction to the account and adjustment.
-                // A withdrawal will have a negative amount so += will be subtraciton.
-                AccountTransaction::apply(account, &mut adjustment, |tx| {
-                    assert_eq!(tx.state, AdjustmentState::Valid);
-                    tx.balance += tx.amount;
-                    Ok(())
-                })?;
+        let amount = amount.ok_or(EngineError::MissingAmount)?;
 
-                // Only once the transaction succeeds do we commit it.
-                entry.insert(adjustment);
-                Ok(())
-            }
+        if self.store.get_adjustment(adjustment_id).is_some() {
+            return Err(EngineError::DuplicateTransaction);
         }
+
+        // Get or create the Account if it doesn't exist.
+        // Also prepare the corresponding Adjustment.
+        let mut account = self.store.get_account(account_id).unwrap_or_default();
+        let locked = account
+            .assets
+            .get(&asset_id)
+            .map(|asset| asset.locked_amount(self.sequence))
+            .unwrap_or(Currency::ZERO);
+        let mut adjustment = Adjustment {
+            account_id,
+            asset_id,
+            amount: to_accredit(amount),
+            state: AdjustmentState::Valid,
+        };
+
+        // Try to apply the transaction to the account and adjustment.
+        // A withdrawal will have a negative amount so += will be subtraciton.
+        AccountTransaction::apply(&mut account, asset_id, &mut adjustment, |tx| {
+            assert_eq!(tx.state, AdjustmentState::Valid);
+            tx.balance = tx
+                .balance
+                .checked_add(tx.amount)
+                .ok_or(EngineError::InvalidAmount)?;
+            // A lock only restricts withdrawal of the locked funds, not
+            // whether a deposit can land, so only a withdrawal is gated by
+            // the floor it sets.
+            if is_withdrawal && tx.balance < locked {
+                return Err(EngineError::InsufficientFunds);
+            }
+            Ok(())
+        })?;
+
+        // Only once the transaction succeeds do we commit it.
+        self.store.put_account(account_id, account);
+        self.store.put_adjustment(adjustment_id, adjustment);
+        self.adjust_issuance(asset_id, adjustment.amount)
     }
 
     fn update_transaction(
         &mut self,
         transaction: Transaction,
         update_tx: impl FnOnce(&mut AccountTransaction) -> Result<()>,
-    ) -> Result<()> {
+    ) -> Result<Adjustment> {
         // Make sure the transaction exists.
         let adjustment_id = transaction.id;
-        let adjustment = self
-            .adjustments
-            .get_mut(&adjustment_id)
-            .ok_or(anyhow!("transaction reference does not exist"))?;
+        let mut adjustment = self
+            .store
+            .get_adjustment(adjustment_id)
+            .ok_or(EngineError::UnknownTransaction { id: adjustment_id })?;
 
         // Make sure it matches the account.
         let account_id = transaction.client_id;
         if adjustment.account_id != account_id {
-            return Err(anyhow!("transaction reference client-mismatch"));
+            return Err(EngineError::ClientMismatch);
         }
+        let asset_id = adjustment.asset_id;
 
         // If the transaction exists, the account must exist as well from insert_transaction().
-        let account = self
-            .accounts
-            .get_mut(&account_id)
+        let mut account = self
+            .store
+            .get_account(account_id)
             .expect("transaction exists without an account");
 
         // Apply the update to the account and adjustment
-        AccountTransaction::apply(account, adjustment, update_tx)
+        AccountTransaction::apply(&mut account, asset_id, &mut adjustment, update_tx)?;
+
+        self.store.put_account(account_id, account);
+        self.store.put_adjustment(adjustment_id, adjustment);
+        Ok(adjustment)
+    }
+
+    /// Moves `amount` of `asset_id` out of a client's available balance and
+    /// into a named reserve pool. Reserved funds stay part of the client's
+    /// holdings (and so don't affect [`Engine::total_issuance`]) but are held
+    /// apart from `balance` until released with [`Engine::unreserve`] or
+    /// permanently removed with [`Engine::slash`].
+    pub fn reserve(
+        &mut self,
+        client_id: ClientId,
+        asset_id: AssetId,
+        pool: impl Into<String>,
+        amount: Currency,
+    ) -> Result<()> {
+        let mut account = self.store.get_account(client_id).unwrap_or_default();
+        if account.is_frozen {
+            return Err(EngineError::FrozenAccount);
+        }
+
+        let asset = account.assets.entry(asset_id).or_default();
+        let locked = asset.locked_amount(self.sequence);
+        let new_balance = asset
+            .balance
+            .checked_sub(amount)
+            .ok_or(EngineError::InvalidAmount)?;
+        if new_balance.is_negative() || new_balance < locked {
+            return Err(EngineError::InsufficientFunds);
+        }
+
+        let reserved = asset.reserved.entry(pool.into()).or_insert(Currency::ZERO);
+        *reserved = reserved.checked_add(amount).ok_or(EngineError::InvalidAmount)?;
+        asset.balance = new_balance;
+
+        self.store.put_account(client_id, account);
+        Ok(())
+    }
+
+    /// Moves `amount` back out of a reserve pool into the client's available
+    /// balance. The inverse of [`Engine::reserve`].
+    pub fn unreserve(
+        &mut self,
+        client_id: ClientId,
+        asset_id: AssetId,
+        pool: impl Into<String>,
+        amount: Currency,
+    ) -> Result<()> {
+        let mut account = self.store.get_account(client_id).unwrap_or_default();
+        if account.is_frozen {
+            return Err(EngineError::FrozenAccount);
+        }
+
+        let asset = account.assets.entry(asset_id).or_default();
+        let reserved = asset.reserved.entry(pool.into()).or_insert(Currency::ZERO);
+        let new_reserved = reserved
+            .checked_sub(amount)
+            .filter(|balance| !balance.is_negative())
+            .ok_or(EngineError::InsufficientFunds)?;
+        asset.balance = asset.balance.checked_add(amount).ok_or(EngineError::InvalidAmount)?;
+        *reserved = new_reserved;
+
+        self.store.put_account(client_id, account);
+        Ok(())
+    }
+
+    /// Permanently destroys `amount` held in a reserve pool, removing it from
+    /// circulation entirely (unlike [`Engine::unreserve`], it never returns to
+    /// `balance`).
+    pub fn slash(
+        &mut self,
+        client_id: ClientId,
+        asset_id: AssetId,
+        pool: impl Into<String>,
+        amount: Currency,
+    ) -> Result<()> {
+        let mut account = self.store.get_account(client_id).unwrap_or_default();
+        if account.is_frozen {
+            return Err(EngineError::FrozenAccount);
+        }
+
+        let asset = account.assets.entry(asset_id).or_default();
+        let reserved = asset.reserved.entry(pool.into()).or_insert(Currency::ZERO);
+        let new_reserved = reserved
+            .checked_sub(amount)
+            .filter(|balance| !balance.is_negative())
+            .ok_or(EngineError::InsufficientFunds)?;
+        *reserved = new_reserved;
+
+        self.store.put_account(client_id, account);
+        self.adjust_issuance(asset_id, -amount)
+    }
+
+    /// Locks `amount` of a client's available balance against withdrawal
+    /// until the engine's transaction sequence reaches `until_sequence`.
+    /// Overlapping locks don't stack; see [`Account`]/[`AssetAccount`] for why.
+    /// Rejects a negative `amount` and one that exceeds the current balance,
+    /// so a lock can never set a floor above funds that actually exist.
+    pub fn lock(
+        &mut self,
+        client_id: ClientId,
+        asset_id: AssetId,
+        amount: Currency,
+        until_sequence: u64,
+    ) -> Result<()> {
+        let mut account = self.store.get_account(client_id).unwrap_or_default();
+        if account.is_frozen {
+            return Err(EngineError::FrozenAccount);
+        }
+        if amount.is_negative() {
+            return Err(EngineError::InvalidAmount);
+        }
+
+        let asset = account.assets.entry(asset_id).or_default();
+        if amount > asset.balance {
+            return Err(EngineError::InsufficientFunds);
+        }
+        asset.locks.retain(|lock| lock.until_sequence > self.sequence);
+        asset.locks.push(Lock { amount, until_sequence });
+
+        self.store.put_account(client_id, account);
+        Ok(())
+    }
+
+    /// The running total of `asset_id` in circulation, maintained
+    /// incrementally as deposits, withdrawals, chargebacks and slashes are
+    /// applied. See [`Engine::assert_invariant`] to check it against the
+    /// ledger's actual holdings.
+    pub fn total_issuance(&self, asset_id: AssetId) -> Currency {
+        self.total_issuance.get(&asset_id).copied().unwrap_or(Currency::ZERO)
+    }
+
+    fn adjust_issuance(&mut self, asset_id: AssetId, delta: Currency) -> Result<()> {
+        let total = self.total_issuance.entry(asset_id).or_insert(Currency::ZERO);
+        *total = total.checked_add(delta).ok_or(EngineError::InvalidAmount)?;
+        Ok(())
+    }
+
+    /// Checks that, for every asset, the sum of every client's available,
+    /// held and reserved funds matches [`Engine::total_issuance`]. Returns
+    /// [`EngineError::LedgerCorrupted`] if the two have diverged.
+    pub fn assert_invariant(&self) -> Result<()> {
+        let mut ledger_totals: HashMap<AssetId, Currency> = HashMap::new();
+        for (_, account) in self.store.accounts() {
+            for (asset_id, asset) in account.assets {
+                let reserved = asset
+                    .reserved
+                    .values()
+                    .copied()
+                    .try_fold(Currency::ZERO, Currency::checked_add)
+                    .ok_or(EngineError::InvalidAmount)?;
+                let held = asset
+                    .balance
+                    .checked_add(asset.disputing)
+                    .and_then(|sum| sum.checked_add(reserved))
+                    .ok_or(EngineError::InvalidAmount)?;
+
+                let total = ledger_totals.entry(asset_id).or_insert(Currency::ZERO);
+                *total = total.checked_add(held).ok_or(EngineError::InvalidAmount)?;
+            }
+        }
+
+        for (asset_id, issuance) in &self.total_issuance {
+            let held = ledger_totals.remove(asset_id).unwrap_or(Currency::ZERO);
+            if held != *issuance {
+                return Err(EngineError::LedgerCorrupted);
+            }
+        }
+        if ledger_totals.values().any(|held| *held != Currency::ZERO) {
+            return Err(EngineError::LedgerCorrupted);
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,28 @@
+use super::api::TransactionId;
+use thiserror::Error;
+
+/// Structured failure reasons for [`crate::Engine::apply`], so callers can
+/// branch on what went wrong instead of matching on an error message.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Error)]
+pub enum EngineError {
+    #[error("transaction attempted on a frozen account")]
+    FrozenAccount,
+    #[error("insufficient funds for the operation")]
+    InsufficientFunds,
+    #[error("transaction {id} does not reference a known transaction")]
+    UnknownTransaction { id: TransactionId },
+    #[error("transaction references a different client than it was recorded under")]
+    ClientMismatch,
+    #[error("transaction is already under dispute")]
+    AlreadyDisputed,
+    #[error("transaction is not currently under dispute")]
+    NotUnderDispute,
+    #[error("transaction id is already in use")]
+    DuplicateTransaction,
+    #[error("deposit/withdrawal is missing its amount")]
+    MissingAmount,
+    #[error("transaction amount is invalid")]
+    InvalidAmount,
+    #[error("ledger total for asset does not match its recorded total issuance")]
+    LedgerCorrupted,
+}
@@ -3,12 +3,24 @@
 
 mod api;
 mod engine;
+mod error;
+mod parallel;
+mod store;
 
 pub use self::{
-    api::{Client, ClientId, Currency, Operation, Transaction, TransactionId},
+    api::{
+        AssetId, Client, ClientId, Currency, CurrencyParseError, Operation, Transaction,
+        TransactionId, BASE_ASSET,
+    },
     engine::Engine,
+    error::EngineError,
+    parallel::ParallelEngine,
+    store::{MemStore, Store},
 };
 
+#[cfg(feature = "sled-store")]
+pub use self::store::SledStore;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -20,44 +32,49 @@ mod tests {
         TX_ID_GEN.fetch_add(1, Ordering::Relaxed)
     }
 
+    // for building Currency amounts from decimal literals in tests
+    fn amount(decimal: &str) -> Currency {
+        decimal.parse().unwrap()
+    }
+
     #[test]
     fn deposit() -> anyhow::Result<()> {
-        let mut engine = Engine::new();
+        let mut engine: Engine = Engine::new();
 
         // Normal Deposit
         engine.apply(Transaction {
             id: gen_tx_id(),
             operation: Operation::Deposit,
             client_id: 0,
-            amount: Some(10.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
         })?;
 
         let client = engine.clients().next().unwrap(); // there should be only one client
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 10.0); // make sure value & client was stored
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("10")); // make sure value & client was stored
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Ignored deposits
-        for (amount, passes) in [
-            (None, false),
-            (Some(Currency::NAN), false),
-            (Some(Currency::INFINITY), false),
-            (Some(0.0), true),
-            (Some(-0.0), true),
+        for (raw, passes) in [
+            (None, false), // missing amount
+            (Some("0"), true),
+            (Some("-0"), true),
         ] {
             let result = engine.apply(Transaction {
                 id: gen_tx_id(),
                 operation: Operation::Deposit,
                 client_id: 0,
-                amount: amount,
+                asset_id: BASE_ASSET,
+                amount: raw.map(amount),
             });
             assert_eq!(result.is_ok(), passes);
 
             // make sure everything stayed the same
             let client = engine.clients().next().unwrap();
             assert!(!client.is_frozen);
-            assert_eq!(client.balance, 10.0);
-            assert_eq!(client.under_dispute, 0.0);
+            assert_eq!(client.balance, amount("10"));
+            assert_eq!(client.under_dispute, Currency::ZERO);
         }
 
         // Multiple deposits
@@ -65,113 +82,118 @@ mod tests {
             id: gen_tx_id(),
             operation: Operation::Deposit,
             client_id: 0,
-            amount: Some(20.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("20")),
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 30.0); // make sure value compounded
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("30")); // make sure value compounded
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Deposits create multiple clients
         engine.apply(Transaction {
             id: gen_tx_id(),
             operation: Operation::Deposit,
             client_id: 1,
-            amount: Some(42.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("42")),
         })?;
 
         let client = engine.clients().find(|c| c.id == 0).unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 30.0);
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("30"));
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         let client = engine.clients().find(|c| c.id == 1).unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 42.0);
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("42"));
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Multiple deposites affect clients separately
         engine.apply(Transaction {
             id: gen_tx_id(),
             operation: Operation::Deposit,
             client_id: 0,
-            amount: Some(10.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
         })?;
 
         engine.apply(Transaction {
             id: gen_tx_id(),
             operation: Operation::Deposit,
             client_id: 1,
-            amount: Some(8.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("8")),
         })?;
 
         let client = engine.clients().find(|c| c.id == 0).unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 40.0);
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("40"));
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         let client = engine.clients().find(|c| c.id == 1).unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 50.0);
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("50"));
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         Ok(())
     }
 
     #[test]
     fn withdraw() -> anyhow::Result<()> {
-        let mut engine = Engine::new();
+        let mut engine: Engine = Engine::new();
 
         // Normal deposit
         engine.apply(Transaction {
             id: gen_tx_id(),
             operation: Operation::Deposit,
             client_id: 0,
-            amount: Some(10.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 10.0);
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("10"));
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Normal Withdrawal
         engine.apply(Transaction {
             id: gen_tx_id(),
             operation: Operation::Withdrawal,
             client_id: 0,
-            amount: Some(7.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("7")),
         })?;
 
         let client = engine.clients().next().unwrap(); // there should be only one client
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 3.0); // make sure value was subtracted from deposit
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("3")); // make sure value was subtracted from deposit
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Ignored withdrawals
-        for (amount, passes) in [
-            (None, false),
-            (Some(Currency::NAN), false),
-            (Some(Currency::INFINITY), false),
-            (Some(0.0), true),
-            (Some(-0.0), true),
-            (Some(9999.0), false),
-            (Some(3.1), false),
+        for (raw, passes) in [
+            (None, false), // missing amount
+            (Some("0"), true),
+            (Some("-0"), true),
+            (Some("9999"), false), // insufficient funds
+            (Some("3.1"), false),  // insufficient funds
         ] {
             let result = engine.apply(Transaction {
                 id: gen_tx_id(),
                 operation: Operation::Withdrawal,
                 client_id: 0,
-                amount: amount,
+                asset_id: BASE_ASSET,
+                amount: raw.map(amount),
             });
             assert_eq!(result.is_ok(), passes);
 
             // make sure everything stayed the same
             let client = engine.clients().next().unwrap();
             assert!(!client.is_frozen);
-            assert_eq!(client.balance, 3.0);
-            assert_eq!(client.under_dispute, 0.0);
+            assert_eq!(client.balance, amount("3"));
+            assert_eq!(client.under_dispute, Currency::ZERO);
         }
 
         Ok(())
@@ -179,7 +201,7 @@ mod tests {
 
     #[test]
     fn dispute_resolve_chargeback() -> anyhow::Result<()> {
-        let mut engine = Engine::new();
+        let mut engine: Engine = Engine::new();
 
         // Normal Deposit
         let deposit_id = gen_tx_id();
@@ -187,13 +209,14 @@ mod tests {
             id: deposit_id,
             operation: Operation::Deposit,
             client_id: 0,
-            amount: Some(10.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
         })?;
 
         let client = engine.clients().next().unwrap(); // there should be only one client
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 10.0); // make sure value & client was stored
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("10")); // make sure value & client was stored
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Normal Withdrawal
         let withdrawal_id = gen_tx_id();
@@ -201,98 +224,105 @@ mod tests {
             id: withdrawal_id,
             operation: Operation::Withdrawal,
             client_id: 0,
-            amount: Some(3.0),
+            asset_id: BASE_ASSET,
+            amount: Some(amount("3")),
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 7.0); // make sure value was subtracted
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("7")); // make sure value was subtracted
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Fail to Dispute deposit (client balance would go negative)
         let bad_tx = engine.apply(Transaction {
             id: deposit_id,
             operation: Operation::Dispute,
             client_id: 0,
+            asset_id: BASE_ASSET,
             amount: None,
         });
         assert!(bad_tx.is_err());
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 7.0); // make sure nothing changed
-        assert_eq!(client.under_dispute, 0.0);
+        assert_eq!(client.balance, amount("7")); // make sure nothing changed
+        assert_eq!(client.under_dispute, Currency::ZERO);
 
         // Dispute withdrawal
         engine.apply(Transaction {
             id: withdrawal_id,
             operation: Operation::Dispute,
             client_id: 0,
-            amount: Some(4.0), // amount should be ignored
+            asset_id: BASE_ASSET,
+            amount: Some(amount("4")), // amount should be ignored
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 10.0); // make sure value goes back to before withdrawal
-        assert_eq!(client.under_dispute, -3.0);
+        assert_eq!(client.balance, amount("10")); // make sure value goes back to before withdrawal
+        assert_eq!(client.under_dispute, amount("-3"));
 
         // Fail to dispute withdrawal multiple times
         let bad_tx = engine.apply(Transaction {
             id: withdrawal_id,
             operation: Operation::Dispute,
             client_id: 0,
-            amount: Some(4.0), // amount should be ignored
+            asset_id: BASE_ASSET,
+            amount: Some(amount("4")), // amount should be ignored
         });
         assert!(bad_tx.is_err());
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 10.0); // make sure nothing changed
-        assert_eq!(client.under_dispute, -3.0);
+        assert_eq!(client.balance, amount("10")); // make sure nothing changed
+        assert_eq!(client.under_dispute, amount("-3"));
 
         // Resolve withdrawal
         engine.apply(Transaction {
             id: withdrawal_id,
             operation: Operation::Resolve,
             client_id: 0,
-            amount: Some(5.0), // amount should be ignored
+            asset_id: BASE_ASSET,
+            amount: Some(amount("5")), // amount should be ignored
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 7.0); // make sure value gets subtracted again
-        assert_eq!(client.under_dispute, 0.0); // make sure this was reset
+        assert_eq!(client.balance, amount("7")); // make sure value gets subtracted again
+        assert_eq!(client.under_dispute, Currency::ZERO); // make sure this was reset
 
         // Dispute withdrawal (again)
         engine.apply(Transaction {
             id: withdrawal_id,
             operation: Operation::Dispute,
             client_id: 0,
-            amount: Some(4.0), // amount should be ignored
+            asset_id: BASE_ASSET,
+            amount: Some(amount("4")), // amount should be ignored
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(!client.is_frozen);
-        assert_eq!(client.balance, 10.0); // make sure value goes back to before withdrawal
-        assert_eq!(client.under_dispute, -3.0);
+        assert_eq!(client.balance, amount("10")); // make sure value goes back to before withdrawal
+        assert_eq!(client.under_dispute, amount("-3"));
 
         // Chargeback
         engine.apply(Transaction {
             id: withdrawal_id,
             operation: Operation::Chargeback,
             client_id: 0,
-            amount: Some(42.0), // amount should be ignored
+            asset_id: BASE_ASSET,
+            amount: Some(amount("42")), // amount should be ignored
         })?;
 
         let client = engine.clients().next().unwrap();
         assert!(client.is_frozen); // client should be frozen
-        assert_eq!(client.balance, 10.0); // make sure value is the same
-        assert_eq!(client.under_dispute, 0.0); // make sure the dispute was settled from chargeback
+        assert_eq!(client.balance, amount("10")); // make sure value is the same
+        assert_eq!(client.under_dispute, Currency::ZERO); // make sure the dispute was settled from chargeback
 
         // Make sure all forms of transactions fail on the frozen account
-        for (id, operation, amount) in [
-            (gen_tx_id(), Operation::Deposit, Some(10.0)),
-            (gen_tx_id(), Operation::Withdrawal, Some(1.0)),
+        for (id, operation, raw) in [
+            (gen_tx_id(), Operation::Deposit, Some("10")),
+            (gen_tx_id(), Operation::Withdrawal, Some("1")),
             (withdrawal_id, Operation::Dispute, None),
             (withdrawal_id, Operation::Resolve, None),
             (withdrawal_id, Operation::Chargeback, None),
@@ -302,16 +332,226 @@ mod tests {
                 id,
                 operation,
                 client_id: 0,
-                amount,
+                asset_id: BASE_ASSET,
+                amount: raw.map(amount),
             });
             assert!(bad_tx.is_err());
 
             let client = engine.clients().next().unwrap();
             assert!(client.is_frozen); // client should still be frozen
-            assert_eq!(client.balance, 10.0); // make sure nothing changed
-            assert_eq!(client.under_dispute, 0.0);
+            assert_eq!(client.balance, amount("10")); // make sure nothing changed
+            assert_eq!(client.under_dispute, Currency::ZERO);
         }
 
         Ok(())
     }
+
+    #[test]
+    fn reserve_unreserve_and_slash() -> anyhow::Result<()> {
+        let mut engine: Engine = Engine::new();
+
+        engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Deposit,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
+        })?;
+
+        // Reserving moves funds out of the available balance but keeps them
+        // part of the client's holdings, so issuance is unaffected.
+        engine.reserve(0, BASE_ASSET, "escrow", amount("4"))?;
+        assert_eq!(engine.clients().next().unwrap().balance, amount("6"));
+        assert_eq!(engine.total_issuance(BASE_ASSET), amount("10"));
+        engine.assert_invariant()?;
+
+        // Can't reserve more than what's available.
+        assert!(engine.reserve(0, BASE_ASSET, "escrow", amount("7")).is_err());
+
+        // Unreserving returns funds to the available balance.
+        engine.unreserve(0, BASE_ASSET, "escrow", amount("1"))?;
+        assert_eq!(engine.clients().next().unwrap().balance, amount("7"));
+        engine.assert_invariant()?;
+
+        // Can't unreserve more than what's in the pool.
+        assert!(engine.unreserve(0, BASE_ASSET, "escrow", amount("100")).is_err());
+
+        // Slashing permanently destroys reserved funds, reducing issuance.
+        engine.slash(0, BASE_ASSET, "escrow", amount("3"))?;
+        assert_eq!(engine.clients().next().unwrap().balance, amount("7"));
+        assert_eq!(engine.total_issuance(BASE_ASSET), amount("7"));
+        engine.assert_invariant()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn time_lock_blocks_withdrawal_until_sequence() -> anyhow::Result<()> {
+        let mut engine: Engine = Engine::new();
+
+        engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Deposit,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
+        })?;
+
+        // Lock 6 until the sequence reaches 4, so the 2nd and 3rd
+        // transactions are bound by it (the deposit above was the 1st).
+        engine.lock(0, BASE_ASSET, amount("6"), 4)?;
+
+        // A withdrawal that would dip below the locked amount is rejected...
+        let bad_tx = engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Withdrawal,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("5")), // 2nd transaction: 10 - 5 = 5 < 6 locked
+        });
+        assert!(bad_tx.is_err());
+        assert_eq!(engine.clients().next().unwrap().balance, amount("10"));
+
+        // ...but a smaller withdrawal that respects the lock still works.
+        engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Withdrawal,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("3")), // 3rd transaction: 10 - 3 = 7 >= 6 locked
+        })?;
+        assert_eq!(engine.clients().next().unwrap().balance, amount("7"));
+
+        // Once the sequence reaches until_sequence, the lock expires and the
+        // full balance is free again.
+        engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Withdrawal,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("7")), // 4th transaction: lock expires at this sequence
+        })?;
+        assert_eq!(engine.clients().next().unwrap().balance, Currency::ZERO);
+
+        engine.assert_invariant()?;
+        Ok(())
+    }
+
+    #[test]
+    fn lock_does_not_gate_deposits() -> anyhow::Result<()> {
+        let mut engine: Engine = Engine::new();
+
+        engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Deposit,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
+        })?;
+
+        // Lock the entire available balance against withdrawal...
+        engine.lock(0, BASE_ASSET, amount("10"), 5)?;
+
+        // ...but a deposit still lands; the lock only restricts withdrawal.
+        engine.apply(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Deposit,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("1")),
+        })?;
+        assert_eq!(engine.clients().next().unwrap().balance, amount("11"));
+
+        engine.assert_invariant()?;
+        Ok(())
+    }
+
+    #[test]
+    fn lock_rejects_amount_above_available_balance() {
+        let mut engine: Engine = Engine::new();
+
+        engine
+            .apply(Transaction {
+                id: gen_tx_id(),
+                operation: Operation::Deposit,
+                client_id: 0,
+                asset_id: BASE_ASSET,
+                amount: Some(amount("10")),
+            })
+            .unwrap();
+
+        assert!(engine.lock(0, BASE_ASSET, amount("11"), 5).is_err());
+        assert!(engine.lock(0, BASE_ASSET, -amount("1"), 5).is_err());
+    }
+
+    #[test]
+    fn parallel_engine_shards_by_client_and_merges_output() {
+        let engine: ParallelEngine = ParallelEngine::new(4);
+
+        // Deposit to several clients, including a withdrawal and a
+        // dispute/chargeback for client 0, all dispatched through the same
+        // shard so ordering is preserved regardless of the other shards.
+        engine.dispatch(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Deposit,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("10")),
+        });
+        let withdrawal_id = gen_tx_id();
+        engine.dispatch(Transaction {
+            id: withdrawal_id,
+            operation: Operation::Withdrawal,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("3")),
+        });
+        engine.dispatch(Transaction {
+            id: withdrawal_id,
+            operation: Operation::Dispute,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: None,
+        });
+        engine.dispatch(Transaction {
+            id: withdrawal_id,
+            operation: Operation::Chargeback,
+            client_id: 0,
+            asset_id: BASE_ASSET,
+            amount: None,
+        });
+        engine.dispatch(Transaction {
+            id: gen_tx_id(),
+            operation: Operation::Deposit,
+            client_id: 1,
+            asset_id: BASE_ASSET,
+            amount: Some(amount("42")),
+        });
+
+        let clients: Vec<_> = engine
+            .join()
+            .into_iter()
+            .flat_map(|shard| shard.clients().collect::<Vec<_>>())
+            .collect();
+
+        let client0 = clients.iter().find(|c| c.id == 0).unwrap();
+        assert!(client0.is_frozen);
+        assert_eq!(client0.balance, amount("10"));
+
+        let client1 = clients.iter().find(|c| c.id == 1).unwrap();
+        assert!(!client1.is_frozen);
+        assert_eq!(client1.balance, amount("42"));
+
+        assert_eq!(clients.len(), 2);
+    }
+
+    #[test]
+    fn currency_parsing() {
+        assert_eq!(amount("1.5"), amount("1.5000"));
+        assert_eq!(amount("-0"), Currency::ZERO);
+        assert!("1.23456".parse::<Currency>().is_err());
+        assert!("not-a-number".parse::<Currency>().is_err());
+        assert!("".parse::<Currency>().is_err());
+        assert_eq!(amount("1.5").to_string(), "1.5");
+    }
 }
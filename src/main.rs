@@ -1,6 +1,7 @@
 use anyhow::Result;
 use clap::Parser;
-use payments_engine::{Engine, Transaction};
+use payments_engine::{ParallelEngine, Transaction};
+use std::thread::available_parallelism;
 
 #[derive(Parser, Debug)]
 struct Arguments {
@@ -14,17 +15,21 @@ fn main() -> Result<()> {
         .flexible(true) // allow missing "amount" fields for non deposit/withdrawal types
         .from_path(args.csv_file_path)?;
 
-    let mut engine = Engine::new();
+    // Transactions for distinct clients never interact, so shard the ledger
+    // across one engine per core and dispatch each transaction to the shard
+    // owning its client.
+    let shard_count = available_parallelism().map_or(1, |n| n.get());
+    let engine: ParallelEngine = ParallelEngine::new(shard_count);
     for result in reader.deserialize() {
         let transaction: Transaction = result?;
-        if let Err(e) = engine.apply(transaction) {
-            eprintln!("{e:?} {transaction:?}");
-        }
+        engine.dispatch(transaction);
     }
 
     let mut writer = csv::Writer::from_writer(std::io::stdout());
-    for client in engine.clients() {
-        writer.serialize(client)?;
+    for shard in engine.join() {
+        for client in shard.clients() {
+            writer.serialize(client)?;
+        }
     }
 
     writer.flush()?;
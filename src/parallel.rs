@@ -0,0 +1,88 @@
+//! Parallel ingestion by sharding the ledger across independent [`Engine`]s.
+//!
+//! Transactions for distinct [`ClientId`]s never interact — every mutation in
+//! [`Engine::apply`] is scoped to a single client's account — so the ledger
+//! can be split into `shard_count` disjoint [`Engine`]s, each owning the
+//! clients for which `client_id % shard_count` picks it, and driven by its
+//! own worker thread. A shard's `Engine` still keys adjustments only by
+//! transaction id, so clients that land on the same shard share its
+//! duplicate-detection: a transaction id must stay unique across every
+//! client routed to that shard, not just within one client's own history.
+
+use super::api::Transaction;
+use super::engine::Engine;
+use super::error::EngineError;
+use super::store::{MemStore, Store};
+use std::sync::mpsc;
+use std::thread;
+
+/// Number of transactions a shard's channel will buffer before `dispatch`
+/// starts blocking the reader.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Drives `shard_count` [`Engine`]s in parallel, each on its own thread.
+pub struct ParallelEngine<S: Store = MemStore> {
+    senders: Vec<mpsc::SyncSender<Transaction>>,
+    workers: Vec<thread::JoinHandle<Engine<S>>>,
+}
+
+impl<S: Store + Send + 'static> ParallelEngine<S> {
+    /// Spawns `shard_count` worker threads, each driving its own `Engine`.
+    pub fn new(shard_count: usize) -> Self {
+        assert!(shard_count > 0, "need at least one shard");
+
+        let mut senders = Vec::with_capacity(shard_count);
+        let mut workers = Vec::with_capacity(shard_count);
+        for _ in 0..shard_count {
+            let (sender, receiver) = mpsc::sync_channel::<Transaction>(CHANNEL_CAPACITY);
+            let worker = thread::spawn(move || {
+                let mut engine: Engine<S> = Engine::new();
+                for transaction in receiver {
+                    if let Err(error) = engine.apply(transaction) {
+                        report_error(error, transaction);
+                    }
+                }
+                engine
+            });
+            senders.push(sender);
+            workers.push(worker);
+        }
+
+        Self { senders, workers }
+    }
+
+    /// Routes `transaction` to the shard owning its `client_id`, blocking if
+    /// that shard's queue is full. Every transaction for a given client goes
+    /// through the same shard's channel, so per-client ordering is preserved
+    /// even though shards run concurrently — critical so a dispute is never
+    /// processed ahead of the deposit it references.
+    pub fn dispatch(&self, transaction: Transaction) {
+        let shard = transaction.client_id as usize % self.senders.len();
+        self.senders[shard]
+            .send(transaction)
+            .expect("shard worker thread panicked");
+    }
+
+    /// Closes every shard's queue, waits for its worker to drain, and
+    /// returns the shards so their `clients()` can be merged for output.
+    pub fn join(self) -> Vec<Engine<S>> {
+        drop(self.senders);
+        self.workers
+            .into_iter()
+            .map(|worker| worker.join().expect("shard worker thread panicked"))
+            .collect()
+    }
+}
+
+/// Logs a rejected transaction, telling apart malformed/replayed input from a
+/// business-rule rejection.
+fn report_error(error: EngineError, transaction: Transaction) {
+    match error {
+        EngineError::UnknownTransaction { .. }
+        | EngineError::ClientMismatch
+        | EngineError::DuplicateTransaction => {
+            eprintln!("malformed transaction ({error}): {transaction:?}")
+        }
+        _ => eprintln!("{error}: {transaction:?}"),
+    }
+}
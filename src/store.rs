@@ -0,0 +1,203 @@
+//! Key-value abstraction over account/adjustment storage.
+//!
+//! `Engine` talks to its ledger data only through the [`Store`] trait, so the
+//! default in-memory [`MemStore`] can be swapped for a disk-backed
+//! implementation without touching the transaction logic in `engine.rs`.
+
+use super::api::{AssetId, ClientId, Currency, TransactionId};
+use std::collections::HashMap;
+
+/// A client's holdings, keyed by asset. `is_frozen` lives here rather than on
+/// each [`AssetAccount`] because a chargeback freezes the whole client, not
+/// just the asset the disputed transaction touched.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "sled-store", derive(serde::Serialize, serde::Deserialize))]
+pub struct Account {
+    pub is_frozen: bool,
+    pub assets: HashMap<AssetId, AssetAccount>,
+}
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "sled-store", derive(serde::Serialize, serde::Deserialize))]
+pub struct AssetAccount {
+    pub balance: Currency,
+    pub disputing: Currency,
+    /// Funds earmarked for a purpose (named so an asset can hold several
+    /// reserves at once), set aside from `balance` via `Engine::reserve`.
+    pub reserved: HashMap<String, Currency>,
+    /// Active time-locks restricting withdrawal of `balance`. Overlapping
+    /// locks don't stack: the effective frozen amount is their maximum, not
+    /// their sum, since a lock describes a minimum balance requirement.
+    pub locks: Vec<Lock>,
+}
+
+impl AssetAccount {
+    /// The portion of `balance` that withdrawals may not touch because it's
+    /// covered by a lock that hasn't reached its `until_sequence` yet.
+    pub fn locked_amount(&self, sequence: u64) -> Currency {
+        self.locks
+            .iter()
+            .filter(|lock| lock.until_sequence > sequence)
+            .map(|lock| lock.amount)
+            .max()
+            .unwrap_or(Currency::ZERO)
+    }
+}
+
+/// A time-lock on some amount of an [`AssetAccount`]'s balance, set by
+/// `Engine::lock` and effective until the engine's transaction sequence
+/// counter reaches `until_sequence`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "sled-store", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lock {
+    pub amount: Currency,
+    pub until_sequence: u64,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "sled-store", derive(serde::Serialize, serde::Deserialize))]
+pub enum AdjustmentState {
+    Valid,
+    UnderDispute,
+    Invalid,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "sled-store", derive(serde::Serialize, serde::Deserialize))]
+pub struct Adjustment {
+    pub account_id: ClientId,
+    pub asset_id: AssetId,
+    pub amount: Currency,
+    pub state: AdjustmentState,
+}
+
+/// Storage backend for [`Account`]s and [`Adjustment`]s.
+///
+/// Implementations get/put whole values rather than handing out references,
+/// which is what lets a backend keep most of the ledger off the heap (e.g.
+/// behind a disk-backed key-value store) instead of requiring everything to
+/// be resident in a `HashMap`.
+pub trait Store: Default {
+    fn get_account(&self, client_id: ClientId) -> Option<Account>;
+    fn put_account(&mut self, client_id: ClientId, account: Account);
+    fn get_adjustment(&self, transaction_id: TransactionId) -> Option<Adjustment>;
+    fn put_adjustment(&mut self, transaction_id: TransactionId, adjustment: Adjustment);
+    fn accounts(&self) -> Box<dyn Iterator<Item = (ClientId, Account)> + '_>;
+}
+
+/// The default `Store`, backed by in-memory `HashMap`s. Caps the ledger size
+/// at whatever fits in RAM; see `sled_store::SledStore` (behind the
+/// `sled-store` feature) for a backend that doesn't.
+#[derive(Default)]
+pub struct MemStore {
+    accounts: HashMap<ClientId, Account>,
+    adjustments: HashMap<TransactionId, Adjustment>,
+}
+
+impl Store for MemStore {
+    fn get_account(&self, client_id: ClientId) -> Option<Account> {
+        self.accounts.get(&client_id).cloned()
+    }
+
+    fn put_account(&mut self, client_id: ClientId, account: Account) {
+        self.accounts.insert(client_id, account);
+    }
+
+    fn get_adjustment(&self, transaction_id: TransactionId) -> Option<Adjustment> {
+        self.adjustments.get(&transaction_id).copied()
+    }
+
+    fn put_adjustment(&mut self, transaction_id: TransactionId, adjustment: Adjustment) {
+        self.adjustments.insert(transaction_id, adjustment);
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = (ClientId, Account)> + '_> {
+        Box::new(self.accounts.iter().map(|(id, account)| (*id, account.clone())))
+    }
+}
+
+#[cfg(feature = "sled-store")]
+pub use sled_store::SledStore;
+
+#[cfg(feature = "sled-store")]
+mod sled_store {
+    use super::{Account, Adjustment, ClientId, Store, TransactionId};
+
+    /// Disk-backed `Store` built on `sled`. An `Engine` reads and writes one
+    /// account and one adjustment per transaction, so with this store only a
+    /// bounded working set of pages is resident, letting multi-GB transaction
+    /// logs be processed without holding every account in RAM.
+    pub struct SledStore {
+        accounts: sled::Tree,
+        adjustments: sled::Tree,
+    }
+
+    impl SledStore {
+        /// Opens (or creates) the account/adjustment trees at `path`.
+        pub fn open(path: impl AsRef<std::path::Path>) -> sled::Result<Self> {
+            let db = sled::open(path)?;
+            Ok(Self {
+                accounts: db.open_tree("accounts")?,
+                adjustments: db.open_tree("adjustments")?,
+            })
+        }
+    }
+
+    impl Default for SledStore {
+        /// Opens a temporary on-disk database. Callers that need a durable
+        /// path should use [`SledStore::open`] instead.
+        fn default() -> Self {
+            let db = sled::Config::new()
+                .temporary(true)
+                .open()
+                .expect("failed to open temporary sled database");
+            Self {
+                accounts: db.open_tree("accounts").expect("failed to open accounts tree"),
+                adjustments: db
+                    .open_tree("adjustments")
+                    .expect("failed to open adjustments tree"),
+            }
+        }
+    }
+
+    impl Store for SledStore {
+        fn get_account(&self, client_id: ClientId) -> Option<Account> {
+            let bytes = self
+                .accounts
+                .get(client_id.to_be_bytes())
+                .expect("sled get failed")?;
+            Some(bincode::deserialize(&bytes).expect("corrupt account record"))
+        }
+
+        fn put_account(&mut self, client_id: ClientId, account: Account) {
+            let bytes = bincode::serialize(&account).expect("failed to encode account");
+            self.accounts
+                .insert(client_id.to_be_bytes(), bytes)
+                .expect("sled insert failed");
+        }
+
+        fn get_adjustment(&self, transaction_id: TransactionId) -> Option<Adjustment> {
+            let bytes = self
+                .adjustments
+                .get(transaction_id.to_be_bytes())
+                .expect("sled get failed")?;
+            Some(bincode::deserialize(&bytes).expect("corrupt adjustment record"))
+        }
+
+        fn put_adjustment(&mut self, transaction_id: TransactionId, adjustment: Adjustment) {
+            let bytes = bincode::serialize(&adjustment).expect("failed to encode adjustment");
+            self.adjustments
+                .insert(transaction_id.to_be_bytes(), bytes)
+                .expect("sled insert failed");
+        }
+
+        fn accounts(&self) -> Box<dyn Iterator<Item = (ClientId, Account)> + '_> {
+            Box::new(self.accounts.iter().map(|entry| {
+                let (key, value) = entry.expect("sled iteration failed");
+                let client_id = ClientId::from_be_bytes(key.as_ref().try_into().unwrap());
+                let account = bincode::deserialize(&value).expect("corrupt account record");
+                (client_id, account)
+            }))
+        }
+    }
+}